@@ -1,18 +1,27 @@
+use projectorctl::config::Config;
 use projectorctl::*;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 struct Cli {
-    #[structopt(short, parse(from_os_str))]
-    path: PathBuf,
+    #[structopt(short, long, parse(from_os_str), default_value = "projectorctl.toml")]
+    config: PathBuf,
+    #[structopt(short, long)]
+    device: String,
     #[structopt(subcommand)]
     command: Command,
 }
 
 fn main() {
     let args: Cli = Cli::from_args();
-    let mut c = Controller::new(args.path.as_path()).unwrap();
+    let config = Config::load(&args.config).expect("Could not load device config");
+    let device_config = config
+        .devices
+        .iter()
+        .find(|d| d.id == args.device)
+        .unwrap_or_else(|| panic!("Unknown device {:?}", args.device));
+    let mut c = Controller::for_device(device_config).unwrap();
     if args.command.is_readable() {
         println!(
             "{:#?}",
@@ -15,87 +15,86 @@ use rocket_okapi::{openapi, openapi_get_routes};
 use std::net::IpAddr;
 use std::path::Path;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::Arc;
+use std::time::Duration;
 
-type ControllerPointer = Arc<Mutex<Controller>>;
-
-fn get_controller(
-    pointer: &State<Arc<Mutex<Controller>>>,
-) -> Result<MutexGuard<Controller>, ControllerErr> {
-    match pointer.inner().lock() {
-        Ok(r) => Ok(r),
-        Err(_) => Err(ControllerErr::SerialPortError),
-    }
+/// Maps a `ControllerErr` onto the JSON error body and HTTP status the API
+/// documents for it, logging the underlying cause.
+fn error_response(e: ControllerErr) -> (Status, Json<ControllerErr>) {
+    warn!("Request failed: {}", e);
+    let status = Status::from_code(e.status_code()).unwrap_or(Status::InternalServerError);
+    (status, Json(e))
 }
 
+/// Serves the cached reply for `device`/`command` unless `fresh` is set or
+/// the state cache hasn't been populated yet, in which case it falls
+/// through to a direct serial read.
 #[openapi]
-#[get("/<command>")]
+#[get("/<device>/<command>?<fresh>")]
 pub fn read(
-    pointer: &State<ControllerPointer>,
+    controllers: &State<Arc<Controllers>>,
+    device: &str,
     command: &str,
+    fresh: Option<bool>,
 ) -> Result<Json<Reply>, (Status, Json<ControllerErr>)> {
-    let controller = get_controller(pointer);
-    let comm = get_command(command, &SubCommand::Status);
-    if let Err(e) = comm {
-        warn!("Cannot parse command {:?}", e);
-        return Err((Status::NotFound, Json(e)));
-    }
-    let comm = comm.unwrap();
-    match controller {
-        Ok(mut c) => match c.read(&comm) {
-            Ok(reply) => {
-                info!("Get state of {:?}: {:?}", comm, reply);
-                Ok(Json(reply))
-            }
-            Err(e) => {
-                warn!("Cannot read from tty {:?}", e);
-                Err((Status::InternalServerError, Json(e)))
-            }
-        },
-        Err(e) => {
-            warn!("Cannot get controller {:?}", e);
-            Err((Status::InternalServerError, Json(e)))
+    let comm = get_command(command, &SubCommand::Status).map_err(error_response)?;
+    if !fresh.unwrap_or(false) {
+        if let Some(cached) = controllers.cached(device, &comm).map_err(error_response)? {
+            info!(
+                "Get state of {} {:?} from cache (age {:?}): {:?}",
+                device,
+                comm,
+                cached.fetched_at.elapsed(),
+                cached.reply
+            );
+            return Ok(Json(cached.reply));
         }
     }
+    let reply = controllers
+        .read_fresh(device, &comm)
+        .map_err(error_response)?;
+    info!("Get state of {} {:?}: {:?}", device, comm, reply);
+    Ok(Json(reply))
 }
 
 #[openapi]
-#[put("/<command>", data = "<subcommand>")]
+#[put("/<device>/<command>", data = "<subcommand>")]
 pub fn write(
-    pointer: &State<ControllerPointer>,
+    controllers: &State<Arc<Controllers>>,
+    device: &str,
     command: &str,
     subcommand: Json<SubCommand>,
 ) -> Result<(), (Status, Json<ControllerErr>)> {
-    let controller = get_controller(pointer);
     if let SubCommand::Status = subcommand.0 {
-        return Err((
-            Status::NotAcceptable,
-            Json(ControllerErr::UnsupportedCommand),
-        ));
+        return Err(error_response(ControllerErr::UnsupportedCommand(
+            "status cannot be written, only read".to_string(),
+        )));
     }
-    let comm = get_command(command, &subcommand.0);
-    if let Err(e) = comm {
-        warn!("Cannot parse command {:?}", e);
-        return Err((Status::NotFound, Json(e)));
-    }
-    let comm = comm.unwrap();
+    let comm = get_command(command, &subcommand.0).map_err(error_response)?;
+    controllers.write(device, &comm).map_err(error_response)?;
+    info!("Set {} {:?}", device, comm);
+    Ok(())
+}
 
-    match controller {
-        Ok(mut c) => match c.write(&comm) {
-            Ok(_) => {
-                info!("Set {:?}", comm);
-                return Ok(());
-            }
-            Err(e) => {
-                warn!("Cannot write to tty {:?}", e);
-                return Err((Status::InternalServerError, Json(e)));
-            }
-        },
-        Err(e) => {
-            warn!("Cannot get controller {:?}", e);
-            return Err((Status::InternalServerError, Json(e)));
-        }
-    }
+#[openapi]
+#[get("/devices")]
+pub fn devices(controllers: &State<Arc<Controllers>>) -> Json<Vec<String>> {
+    Json(
+        controllers
+            .device_ids()
+            .into_iter()
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// Spawns the background thread that keeps every device's state cache
+/// warm, so `read` can usually answer without touching the serial port.
+fn spawn_state_poller(controllers: Arc<Controllers>, interval: Duration) {
+    std::thread::spawn(move || loop {
+        controllers.poll_all();
+        std::thread::sleep(interval);
+    });
 }
 
 #[launch]
@@ -105,12 +104,50 @@ fn rocket() -> _ {
     config.port = 43880;
     config.log_level = LogLevel::Normal;
 
-    let controller = Controller::new(Path::new("/dev/ttyUSB0"));
-    let pointer = Arc::new(Mutex::new(controller.expect("Controller was not created")));
+    let devices_config = projectorctl::config::Config::load(Path::new("projectorctl.toml"))
+        .expect("Could not load device config");
+    let controllers =
+        Controllers::from_config(&devices_config).expect("Controllers were not created");
+    let controllers = Arc::new(controllers);
+
+    spawn_state_poller(
+        Arc::clone(&controllers),
+        Duration::from_secs(devices_config.poll_interval_secs),
+    );
+
+    #[cfg(feature = "mqtt")]
+    spawn_mqtt_bridge(&devices_config);
 
     rocket::custom(config)
-        .manage(pointer)
-        .mount("/", openapi_get_routes![read, write])
+        .manage(controllers)
+        .mount("/", openapi_get_routes![read, write, devices])
+}
+
+#[cfg(feature = "mqtt")]
+fn spawn_mqtt_bridge(devices_config: &projectorctl::config::Config) {
+    use projectorctl::mqtt::MqttBridge;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    for device in &devices_config.devices {
+        let controller = match Controller::for_device(device) {
+            Ok(c) => Arc::new(Mutex::new(c)),
+            Err(e) => {
+                warn!("Cannot open {} for MQTT bridge: {:?}", device.id, e);
+                continue;
+            }
+        };
+        let topic_prefix = format!("projector/{}", device.id);
+        let (bridge, connection) = MqttBridge::new(
+            "localhost",
+            1883,
+            &topic_prefix,
+            Duration::from_secs(30),
+            controller,
+        )
+        .expect("MQTT bridge could not connect to the broker");
+        std::thread::spawn(move || bridge.run(connection));
+    }
 }
 
 fn get_command(command: &str, state: &SubCommand) -> Result<Command, ControllerErr> {
@@ -121,7 +158,10 @@ fn get_command(command: &str, state: &SubCommand) -> Result<Command, ControllerE
         "brightness" => Ok(Command::Brightness(state.clone())),
         "volume" => Ok(Command::Volume(state.clone())),
         "mute" => Ok(Command::Mute(state.clone())),
-        "lamp_time" => Ok(Command::Mute(state.clone())),
-        _ => Err(ControllerErr::UnsupportedCommand),
+        "lamp_time" => Ok(Command::LampTime),
+        _ => Err(ControllerErr::UnsupportedCommand(format!(
+            "unknown command {:?}",
+            command
+        ))),
     }
 }
@@ -0,0 +1,57 @@
+//! A per-device cache of the latest [`Reply`] seen for each readable
+//! command, refreshed by a background poller so the REST API can answer
+//! most `GET` requests without a serial round-trip.
+
+use crate::Reply;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// A cached reply together with when it was fetched, so callers can log or
+/// reason about how stale it is.
+#[derive(Clone)]
+pub struct CacheEntry {
+    pub reply: Reply,
+    pub fetched_at: Instant,
+}
+
+/// Keyed the same way as [`crate::model::ProjectorModel`]'s frame table
+/// (e.g. `"power.status"`), so the poller and the REST handlers agree on
+/// cache keys without the cache needing to know about `Command`.
+#[derive(Default)]
+pub struct StateCache {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl StateCache {
+    pub fn new() -> StateCache {
+        StateCache::default()
+    }
+
+    /// Returns the cached entry for `key`, if one has been [`set`](Self::set)
+    /// and not [`invalidate`](Self::invalidate)d since.
+    pub fn get(&self, key: &str) -> Option<CacheEntry> {
+        let entries = self.entries.lock().ok()?;
+        entries.get(key).cloned()
+    }
+
+    pub fn set(&self, key: String, reply: Reply) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(
+                key,
+                CacheEntry {
+                    reply,
+                    fetched_at: Instant::now(),
+                },
+            );
+        }
+    }
+
+    /// Drops `key`'s cached entry, used after a `write` so the next read
+    /// falls through to the port instead of serving a now-stale value.
+    pub fn invalidate(&self, key: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.remove(key);
+        }
+    }
+}
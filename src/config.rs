@@ -0,0 +1,70 @@
+//! On-disk configuration listing the projectors a `projectorctl` process
+//! should manage.
+
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+fn default_baud_rate() -> u32 {
+    crate::DEFAULT_BAUD_RATE
+}
+
+fn default_poll_interval_secs() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceConfig {
+    /// Name clients use to address this device, e.g. in `/{device}/power`.
+    pub id: String,
+    pub path: PathBuf,
+    #[serde(default = "default_baud_rate")]
+    pub baud: u32,
+    /// Projector model used to look up this device's command table via
+    /// [`crate::model::ProjectorModel::by_name`]: `"benq"` or unset for the
+    /// built-in BenQ model, otherwise a path to a model file loaded via
+    /// [`crate::model::ProjectorModel::load`].
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+    /// How often, in seconds, the REST API's background poller refreshes
+    /// its state cache; see [`crate::cache::StateCache`].
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            devices: Vec::new(),
+            poll_interval_secs: default_poll_interval_secs(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        toml::from_str(&text).map_err(ConfigError::Parse)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "can't read config file: {}", e),
+            ConfigError::Parse(e) => write!(f, "can't parse config file: {}", e),
+        }
+    }
+}
@@ -4,15 +4,30 @@ use log::warn;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serialport::posix::TTYPort;
-use serialport::{DataBits, FlowControl, Parity, SerialPortSettings, StopBits};
+use serialport::{
+    ClearBuffer, DataBits, FlowControl, Parity, SerialPort, SerialPortSettings, StopBits,
+};
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::io::{Read, Write};
 use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
 use std::time::Duration;
 use structopt::StructOpt;
 use Command::*;
 use SubCommand::*;
 
+pub mod cache;
+pub mod config;
+pub mod model;
+
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+
+use cache::StateCache;
+use config::{Config, DeviceConfig};
+use model::ProjectorModel;
+
 #[derive(StructOpt, Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(tag = "State")]
 pub enum SubCommand {
@@ -32,7 +47,7 @@ pub enum Command {
     LampTime,
 }
 
-#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub enum Reply {
     State(bool),
     ValueU8(u8),
@@ -41,9 +56,68 @@ pub enum Reply {
 
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub enum ControllerErr {
-    SerialPortError,
+    /// A serial read did not complete within the port's timeout.
+    Timeout(String),
+    /// A lower-level I/O failure, e.g. opening the port or writing to it.
+    /// Carries a description rather than the source error, since the
+    /// latter isn't `Serialize`.
+    Io(String),
+    /// The reply frame was incomplete or otherwise couldn't be parsed.
+    MalformedFrame(String),
+    /// The reply frame's checksum byte didn't match the computed one.
+    ChecksumMismatch { expected: u8, actual: u8 },
+    /// The command can't be carried out: it isn't in the projector model's
+    /// command table, has no readable reply, or isn't a command name or
+    /// request the caller's interface accepts.
+    UnsupportedCommand(String),
+    /// No device with this id is present in the device config.
+    UnknownDevice(String),
+    /// A read other than the power state itself was attempted while the
+    /// projector is powered off.
     PowerIsDown,
-    UnsupportedCommand,
+}
+
+impl Display for ControllerErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControllerErr::Timeout(context) => write!(f, "timed out while {}", context),
+            ControllerErr::Io(context) => write!(f, "I/O error: {}", context),
+            ControllerErr::MalformedFrame(reason) => write!(f, "malformed reply frame: {}", reason),
+            ControllerErr::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "reply checksum mismatch: expected {:#04x}, got {:#04x}",
+                expected, actual
+            ),
+            ControllerErr::UnsupportedCommand(command) => {
+                write!(
+                    f,
+                    "command not supported by this projector model: {}",
+                    command
+                )
+            }
+            ControllerErr::UnknownDevice(id) => write!(f, "unknown device {:?}", id),
+            ControllerErr::PowerIsDown => write!(f, "projector is powered down"),
+        }
+    }
+}
+
+impl std::error::Error for ControllerErr {}
+
+impl ControllerErr {
+    /// The HTTP status a REST API should report for this error. Returned
+    /// as a plain status code so this crate doesn't have to depend on a
+    /// particular web framework's type for it.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            ControllerErr::Timeout(_) => 504,
+            ControllerErr::Io(_)
+            | ControllerErr::MalformedFrame(_)
+            | ControllerErr::ChecksumMismatch { .. } => 502,
+            ControllerErr::UnknownDevice(_) => 404,
+            ControllerErr::UnsupportedCommand(_) => 406,
+            ControllerErr::PowerIsDown => 409,
+        }
+    }
 }
 
 impl Display for Reply {
@@ -69,14 +143,75 @@ impl Command {
             _ => false,
         }
     }
+
+    /// Every readable command, used by [`Controllers::poll_all`] to refresh
+    /// the state cache without needing a list of commands from its caller.
+    pub fn readable() -> Vec<Command> {
+        vec![
+            Power(Status),
+            Eco(Status),
+            Brightness(Status),
+            Volume(Status),
+            Mute(Status),
+            Source(Status),
+            LampTime,
+        ]
+    }
 }
 
-pub struct Controller(TTYPort);
+/// Number of times `tty_send_frame` sends a command, the first try included,
+/// when reads time out or checksums fail; used by [`Controller::new`] and
+/// [`Controller::for_device`].
+const DEFAULT_ATTEMPTS: usize = 3;
+
+/// Baud rate used when a [`config::DeviceConfig`] doesn't specify one; also
+/// the default for [`Controller::new`].
+pub(crate) const DEFAULT_BAUD_RATE: u32 = 115200;
+
+pub struct Controller {
+    tty: TTYPort,
+    attempts: usize,
+    model: ProjectorModel,
+}
 
 impl Controller {
     pub fn new(path: &Path) -> Result<Controller, ControllerErr> {
+        Self::open(
+            path,
+            DEFAULT_BAUD_RATE,
+            DEFAULT_ATTEMPTS,
+            ProjectorModel::benq(),
+        )
+    }
+
+    /// Like [`Controller::new`], but lets the caller choose how many times a
+    /// command is sent, the first try included, when reads time out or
+    /// checksums fail before giving up. `attempts` is clamped to at least 1.
+    pub fn with_retries(path: &Path, attempts: usize) -> Result<Controller, ControllerErr> {
+        Self::open(path, DEFAULT_BAUD_RATE, attempts, ProjectorModel::benq())
+    }
+
+    /// Opens the `device`'s serial path at its configured baud rate and
+    /// model, using the default retry count. Falls back to the built-in
+    /// BenQ model when the device doesn't name one.
+    pub fn for_device(device: &DeviceConfig) -> Result<Controller, ControllerErr> {
+        let model = ProjectorModel::by_name(device.model.as_deref()).map_err(|e| {
+            ControllerErr::Io(format!(
+                "can't resolve projector model {:?}: {}",
+                device.model, e
+            ))
+        })?;
+        Self::open(&device.path, device.baud, DEFAULT_ATTEMPTS, model)
+    }
+
+    fn open(
+        path: &Path,
+        baud_rate: u32,
+        attempts: usize,
+        model: ProjectorModel,
+    ) -> Result<Controller, ControllerErr> {
         let settings = SerialPortSettings {
-            baud_rate: 115200,
+            baud_rate,
             data_bits: DataBits::Eight,
             flow_control: FlowControl::None,
             parity: Parity::None,
@@ -84,101 +219,276 @@ impl Controller {
             timeout: Duration::new(2, 0),
         };
         match TTYPort::open(path, &settings) {
-            Ok(t) => Ok(Controller(t)),
-            Err(_) => Err(ControllerErr::SerialPortError),
+            Ok(t) => Ok(Controller {
+                tty: t,
+                attempts: attempts.max(1),
+                model,
+            }),
+            Err(e) => Err(ControllerErr::Io(format!("can't open serial port: {}", e))),
         }
     }
 
     pub fn read(&mut self, command: &Command) -> Result<Reply, ControllerErr> {
-        let power_state =
-            parse_state(self.tty_send("\x07\x14\x00\x05\x00\x34\x00\x00\x11\x00\x5E")?);
+        let power_state = self.send_and_parse(&Power(Status))?;
         if let Power(Status) = command {
             return Ok(power_state);
         };
         if let Reply::State(false) = power_state {
             return Err(ControllerErr::PowerIsDown);
         };
-        match command {
-            Eco(Status) => Ok(parse_eco_state(
-                self.tty_send("\x07\x14\x00\x05\x00\x34\x00\x00\x11\x10\x6E")?,
-            )),
-            Brightness(Status) => Ok(parse_value_u8(
-                self.tty_send("\x07\x14\x00\x05\x00\x34\x00\x00\x12\x03\x62")?,
-            )),
-            Volume(Status) => Ok(parse_value_u8(
-                self.tty_send("\x07\x14\x00\x05\x00\x34\x00\x00\x14\x03\x64")?,
-            )),
-            Mute(Status) => Ok(parse_state(
-                self.tty_send("\x07\x14\x00\x05\x00\x34\x00\x00\x14\x00\x61")?,
-            )),
-            Source(Status) => Ok(parse_value_u8(
-                self.tty_send("\x07\x14\x00\x05\x00\x34\x00\x00\x13\x01\x61")?,
-            )),
-            LampTime => Ok(parse_value_u32(
-                self.tty_send("\x07\x14\x00\x05\x00\x34\x00\x00\x15\x01\x63")?,
-            )),
-            _ => Err(ControllerErr::UnsupportedCommand),
-        }
+        self.send_and_parse(command)
     }
 
     pub fn write(&mut self, command: &Command) -> Result<(), ControllerErr> {
-        let res = match command {
-            Power(Up) => self.tty_send("\x06\x14\x00\x04\x00\x34\x11\x00\x00\x5D"),
-            Power(Down) => self.tty_send("\x06\x14\x00\x04\x00\x34\x11\x01\x00\x5E"),
-            Source(Up) => self.tty_send("\x06\x14\x00\x04\x00\x34\x13\x01\x03\x63"),
-            Source(Down) => self.tty_send("\x06\x14\x00\x04\x00\x34\x13\x01\x07\x67"),
-            Eco(Up) => self.tty_send("\x06\x14\x00\x04\x00\x34\x11\x10\x03\x70"),
-            Eco(Down) => self.tty_send("\x06\x14\x00\x04\x00\x34\x11\x10\x02\x6F"),
-            Volume(Up) => self.tty_send("\x06\x14\x00\x04\x00\x34\x14\x01\x00\x61"),
-            Volume(Down) => self.tty_send("\x06\x14\x00\x04\x00\x34\x14\x02\x00\x62"),
-            Mute(Up) => self.tty_send("\x06\x14\x00\x04\x00\x34\x14\x00\x01\x61"),
-            Mute(Down) => self.tty_send("\x06\x14\x00\x04\x00\x34\x14\x00\x00\x60"),
-            Brightness(Up) => self.tty_send("\x06\x14\x00\x04\x00\x34\x12\x03\x01\x62"),
-            Brightness(Down) => self.tty_send("\x06\x14\x00\x04\x00\x34\x12\x03\x00\x61"),
-            _ => Err(ControllerErr::UnsupportedCommand),
-        };
-        match res {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
+        let template = self.model.frame_for(command)?.clone();
+        self.tty_send_frame(&template.frame)?;
+        Ok(())
+    }
+
+    fn send_and_parse(&mut self, command: &Command) -> Result<Reply, ControllerErr> {
+        let template = self.model.frame_for(command)?.clone();
+        let reply_kind = template.reply.ok_or_else(|| {
+            ControllerErr::UnsupportedCommand(format!("{:?} has no readable reply", command))
+        })?;
+        let data = self.tty_send_frame(&template.frame)?;
+        Ok(reply_kind.parse(&data))
+    }
+
+    /// Appends the frame's checksum to `body` and sends it, trying up to
+    /// `self.attempts` times in total while a read times out or its checksum
+    /// does not match. Any other error is returned without a retry.
+    fn tty_send_frame(&mut self, body: &[u8]) -> Result<Vec<u8>, ControllerErr> {
+        let mut command = body.to_vec();
+        command.push(checksum(body));
+
+        let mut last_err = None;
+        for attempt in 0..self.attempts {
+            if attempt > 0 {
+                warn!(
+                    "Retrying tty command after {}, attempt {}/{}",
+                    last_err.as_ref().unwrap(),
+                    attempt + 1,
+                    self.attempts
+                );
+                let _ = self.tty.clear(ClearBuffer::Input);
+            }
+            match self.tty_send_once(&command) {
+                Ok(frame) => return Ok(frame),
+                Err(e @ ControllerErr::Timeout(_))
+                | Err(e @ ControllerErr::ChecksumMismatch { .. }) => last_err = Some(e),
+                Err(e) => return Err(e),
+            }
         }
+        Err(last_err.expect("attempts is always at least 1"))
     }
 
-    fn tty_send(&mut self, command: &str) -> Result<Vec<u8>, ControllerErr> {
-        let tty = &mut self.0;
-        if let Err(e) = tty.write(command.as_ref()) {
+    fn tty_send_once(&mut self, command: &[u8]) -> Result<Vec<u8>, ControllerErr> {
+        let tty = &mut self.tty;
+        if let Err(e) = tty.write(command) {
             warn!("Can't write command to tty {:?}", e);
-            return Err(ControllerErr::SerialPortError);
+            return Err(io_err("writing command to tty", e));
         }
-        let mut serial_buf: Vec<u8> = vec![0; 5];
-        if let Err(e) = tty.read(serial_buf.as_mut_slice()) {
-            warn!("Can't read first 5 bytes from tty {:?}", e);
-            return Err(ControllerErr::SerialPortError);
+        let mut header = [0u8; 5];
+        read_exact(tty, &mut header)?;
+        print!("Tty response: {:?}", header);
+        let mut frame = header.to_vec();
+        frame.resize((header[3] as usize + 1).max(frame.len()), 0);
+        let tail_start = header.len();
+        read_exact(tty, &mut frame[tail_start..])?;
+        println!("{:?}", frame);
+        let (body, reply_checksum) = frame.split_at(frame.len() - 1);
+        let expected = checksum(body);
+        if expected != reply_checksum[0] {
+            warn!("Checksum mismatch in reply {:?}", frame);
+            return Err(ControllerErr::ChecksumMismatch {
+                expected,
+                actual: reply_checksum[0],
+            });
         }
-        print!("Tty response: {:?}", serial_buf);
-        serial_buf.resize((serial_buf[3] + 1) as usize, 0);
-        if let Err(e) = tty.read(serial_buf.as_mut_slice()) {
-            warn!("Can't read last bytes from tty {:?}", e);
-            return Err(ControllerErr::SerialPortError);
+        Ok(frame)
+    }
+}
+
+/// A locked [`Controller`] paired with the [`StateCache`] that backs its
+/// cheap reads.
+struct DeviceEntry {
+    controller: Mutex<Controller>,
+    cache: StateCache,
+}
+
+/// One [`DeviceEntry`] per configured device, keyed by [`DeviceConfig::id`],
+/// so a single process can address several projectors at once.
+pub struct Controllers(HashMap<String, DeviceEntry>);
+
+impl Controllers {
+    /// Opens a [`Controller`] for every configured device. A device whose
+    /// controller fails to open (e.g. a bad serial path or an unresolvable
+    /// projector model) is logged and skipped rather than failing startup
+    /// for every other configured device.
+    pub fn from_config(config: &Config) -> Result<Controllers, ControllerErr> {
+        let mut controllers = HashMap::with_capacity(config.devices.len());
+        for device in &config.devices {
+            match Controller::for_device(device) {
+                Ok(controller) => {
+                    controllers.insert(
+                        device.id.clone(),
+                        DeviceEntry {
+                            controller: Mutex::new(controller),
+                            cache: StateCache::new(),
+                        },
+                    );
+                }
+                Err(e) => warn!("Can't open device {}, skipping it: {}", device.id, e),
+            }
+        }
+        Ok(Controllers(controllers))
+    }
+
+    /// Locks and returns the controller for `device_id`, or
+    /// `ControllerErr::UnknownDevice` if no such device was configured.
+    pub fn get(&self, device_id: &str) -> Result<MutexGuard<Controller>, ControllerErr> {
+        self.entry(device_id)?
+            .controller
+            .lock()
+            .map_err(|_| ControllerErr::Io("controller mutex was poisoned".to_string()))
+    }
+
+    /// The cached reply for `command` on `device_id`, if the background
+    /// poller or [`Controllers::read_fresh`] has stored one and no
+    /// [`Controllers::write`] has invalidated it since.
+    pub fn cached(
+        &self,
+        device_id: &str,
+        command: &Command,
+    ) -> Result<Option<cache::CacheEntry>, ControllerErr> {
+        Ok(self
+            .entry(device_id)?
+            .cache
+            .get(&model::command_key(command)))
+    }
+
+    /// Reads `command` directly from `device_id`'s port and refreshes its
+    /// cache entry with the result.
+    pub fn read_fresh(&self, device_id: &str, command: &Command) -> Result<Reply, ControllerErr> {
+        let entry = self.entry(device_id)?;
+        let mut controller = entry
+            .controller
+            .lock()
+            .map_err(|_| ControllerErr::Io("controller mutex was poisoned".to_string()))?;
+        let reply = controller.read(command)?;
+        entry.cache.set(model::command_key(command), reply.clone());
+        Ok(reply)
+    }
+
+    /// Writes `command` to `device_id`'s port and invalidates its cache
+    /// entry, since the cached reply no longer reflects the new state.
+    pub fn write(&self, device_id: &str, command: &Command) -> Result<(), ControllerErr> {
+        let entry = self.entry(device_id)?;
+        let mut controller = entry
+            .controller
+            .lock()
+            .map_err(|_| ControllerErr::Io("controller mutex was poisoned".to_string()))?;
+        controller.write(command)?;
+        entry.cache.invalidate(&model::status_key(command));
+        Ok(())
+    }
+
+    /// Refreshes every device's cache by issuing a read for each readable
+    /// command, meant to be called on an interval from a background
+    /// thread. Devices whose mutex is poisoned are skipped; read failures
+    /// leave the previous cache entry (if any) in place and are logged,
+    /// except `PowerIsDown`, which is skipped silently.
+    pub fn poll_all(&self) {
+        for (device_id, entry) in &self.0 {
+            let mut controller = match entry.controller.lock() {
+                Ok(c) => c,
+                Err(_) => {
+                    warn!("Controller mutex for {} poisoned, skipping poll", device_id);
+                    continue;
+                }
+            };
+            for command in Command::readable() {
+                match controller.read(&command) {
+                    Ok(reply) => entry.cache.set(model::command_key(&command), reply),
+                    Err(ControllerErr::PowerIsDown) => {}
+                    Err(e) => warn!("Can't refresh {} {:?}: {}", device_id, command, e),
+                }
+            }
         }
-        println!("{:?}", serial_buf);
-        Ok(serial_buf)
+    }
+
+    pub fn device_ids(&self) -> Vec<&str> {
+        self.0.keys().map(String::as_str).collect()
+    }
+
+    fn entry(&self, device_id: &str) -> Result<&DeviceEntry, ControllerErr> {
+        self.0
+            .get(device_id)
+            .ok_or_else(|| ControllerErr::UnknownDevice(device_id.to_string()))
     }
 }
 
-fn parse_state(data: Vec<u8>) -> Reply {
-    Reply::State(data[2] > 0)
+/// Reads from `tty` until `buf` is completely filled, accumulating across
+/// however many short reads `serialport::Read::read` decides to return,
+/// since a single call is not guaranteed to fill the whole slice.
+fn read_exact(tty: &mut TTYPort, buf: &mut [u8]) -> Result<(), ControllerErr> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match tty.read(&mut buf[filled..]) {
+            Ok(0) => {
+                warn!("Tty closed the connection mid-frame");
+                return Err(ControllerErr::MalformedFrame(
+                    "connection closed before the frame was complete".to_string(),
+                ));
+            }
+            Ok(n) => filled += n,
+            Err(e) => {
+                warn!("Can't read from tty {:?}", e);
+                return Err(io_err("reading reply from tty", e));
+            }
+        }
+    }
+    Ok(())
 }
 
-fn parse_eco_state(data: Vec<u8>) -> Reply {
-    Reply::State(data[2] == 3)
+/// Turns a timed-out read into `ControllerErr::Timeout` and anything else
+/// into `ControllerErr::Io`, both carrying `context`.
+fn io_err(context: &str, e: std::io::Error) -> ControllerErr {
+    if e.kind() == std::io::ErrorKind::TimedOut {
+        ControllerErr::Timeout(context.to_string())
+    } else {
+        ControllerErr::Io(format!("{}: {}", context, e))
+    }
 }
 
-fn parse_value_u8(data: Vec<u8>) -> Reply {
-    Reply::ValueU8(data[2])
+/// The leading direction marker (`0x06` for a write, `0x07` for a read)
+/// every BenQ frame starts with; it is not itself part of the checksum.
+const MARKER_LEN: usize = 1;
+
+/// Computes the BenQ-style frame checksum: the sum, modulo 256, of every
+/// byte in `frame` after its leading `0x06`/`0x07` marker (the checksum
+/// byte itself is not part of `frame`).
+fn checksum(frame: &[u8]) -> u8 {
+    frame[MARKER_LEN..]
+        .iter()
+        .fold(0u8, |acc, b| acc.wrapping_add(*b))
 }
 
-fn parse_value_u32(mut data: Vec<u8>) -> Reply {
-    data.reverse();
-    let d = data.as_chunks().0[0];
-    Reply::ValueU32(u32::from_be_bytes(d))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_matches_known_benq_commands() {
+        // Power(Status) read command frame, trailing checksum byte 0x5E.
+        assert_eq!(
+            checksum(&[0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x11, 0x00]),
+            0x5E
+        );
+        // Eco(Status) read command frame, trailing checksum byte 0x6E.
+        assert_eq!(
+            checksum(&[0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x11, 0x10]),
+            0x6E
+        );
+    }
 }
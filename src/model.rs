@@ -0,0 +1,272 @@
+//! Data-driven description of a projector's command set, so models other
+//! than the built-in BenQ one can be supported without touching
+//! [`crate::Controller`].
+
+use crate::config::ConfigError;
+use crate::{Command, ControllerErr, Reply, SubCommand};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use Command::*;
+use SubCommand::*;
+
+/// How a reply frame's payload byte(s) are turned into a [`Reply`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum ReplyKind {
+    State,
+    EcoState,
+    ValueU8,
+    ValueU32,
+}
+
+impl ReplyKind {
+    pub fn parse(&self, data: &[u8]) -> Reply {
+        match self {
+            ReplyKind::State => Reply::State(data[2] > 0),
+            ReplyKind::EcoState => Reply::State(data[2] == 3),
+            ReplyKind::ValueU8 => Reply::ValueU8(data[2]),
+            ReplyKind::ValueU32 => {
+                let mut data = data.to_vec();
+                data.reverse();
+                let d = data.as_chunks().0[0];
+                Reply::ValueU32(u32::from_be_bytes(d))
+            }
+        }
+    }
+}
+
+/// An outgoing command's frame, stored without its trailing checksum byte;
+/// `Controller::tty_send_frame` appends it via `checksum`. `reply` is `Some`
+/// for commands whose reply payload should be parsed into a [`Reply`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameTemplate {
+    pub frame: Vec<u8>,
+    pub reply: Option<ReplyKind>,
+}
+
+/// Maps every `Command`/`SubCommand` combination a projector model supports
+/// to its [`FrameTemplate`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectorModel {
+    pub name: String,
+    commands: HashMap<String, FrameTemplate>,
+}
+
+impl ProjectorModel {
+    /// Loads a model's command table from a TOML file in the same shape
+    /// [`ProjectorModel::benq`] builds in code, so a projector other than
+    /// the built-in BenQ one can be supported without a Rust change.
+    pub fn load(path: &Path) -> Result<ProjectorModel, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        toml::from_str(&text).map_err(ConfigError::Parse)
+    }
+
+    /// Resolves a model by name: `None`/`Some("benq")` use the built-in
+    /// BenQ table, and any other name is loaded as a model file path via
+    /// [`ProjectorModel::load`].
+    pub fn by_name(name: Option<&str>) -> Result<ProjectorModel, ConfigError> {
+        match name {
+            None | Some("benq") => Ok(ProjectorModel::benq()),
+            Some(path) => ProjectorModel::load(Path::new(path)),
+        }
+    }
+
+    pub fn frame_for(&self, command: &Command) -> Result<&FrameTemplate, ControllerErr> {
+        self.commands.get(&command_key(command)).ok_or_else(|| {
+            ControllerErr::UnsupportedCommand(format!(
+                "{:?} is not supported by the {} model",
+                command, self.name
+            ))
+        })
+    }
+
+    /// The command set of today's hardcoded BenQ projector support, kept as
+    /// the default built-in model.
+    pub fn benq() -> ProjectorModel {
+        let mut commands = HashMap::new();
+        let mut insert = |command: Command, frame: &[u8], reply: Option<ReplyKind>| {
+            commands.insert(
+                command_key(&command),
+                FrameTemplate {
+                    frame: frame.to_vec(),
+                    reply,
+                },
+            );
+        };
+
+        insert(
+            Power(Status),
+            &[0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x11, 0x00],
+            Some(ReplyKind::State),
+        );
+        insert(
+            Eco(Status),
+            &[0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x11, 0x10],
+            Some(ReplyKind::EcoState),
+        );
+        insert(
+            Brightness(Status),
+            &[0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x12, 0x03],
+            Some(ReplyKind::ValueU8),
+        );
+        insert(
+            Volume(Status),
+            &[0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x14, 0x03],
+            Some(ReplyKind::ValueU8),
+        );
+        insert(
+            Mute(Status),
+            &[0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x14, 0x00],
+            Some(ReplyKind::State),
+        );
+        insert(
+            Source(Status),
+            &[0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x13, 0x01],
+            Some(ReplyKind::ValueU8),
+        );
+        insert(
+            LampTime,
+            &[0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x15, 0x01],
+            Some(ReplyKind::ValueU32),
+        );
+
+        insert(
+            Power(Up),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x11, 0x00, 0x00],
+            None,
+        );
+        insert(
+            Power(Down),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x11, 0x01, 0x00],
+            None,
+        );
+        insert(
+            Source(Up),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x13, 0x01, 0x03],
+            None,
+        );
+        insert(
+            Source(Down),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x13, 0x01, 0x07],
+            None,
+        );
+        insert(
+            Eco(Up),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x11, 0x10, 0x03],
+            None,
+        );
+        insert(
+            Eco(Down),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x11, 0x10, 0x02],
+            None,
+        );
+        insert(
+            Volume(Up),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x14, 0x01, 0x00],
+            None,
+        );
+        insert(
+            Volume(Down),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x14, 0x02, 0x00],
+            None,
+        );
+        insert(
+            Mute(Up),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x14, 0x00, 0x01],
+            None,
+        );
+        insert(
+            Mute(Down),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x14, 0x00, 0x00],
+            None,
+        );
+        insert(
+            Brightness(Up),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x12, 0x03, 0x01],
+            None,
+        );
+        insert(
+            Brightness(Down),
+            &[0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x12, 0x03, 0x00],
+            None,
+        );
+
+        ProjectorModel {
+            name: "benq".to_string(),
+            commands,
+        }
+    }
+}
+
+/// Flattens a `Command`/`SubCommand` pair into the table key used by
+/// [`ProjectorModel::frame_for`] and, with the same scheme, by
+/// [`crate::cache::StateCache`], e.g. `Power(Up)` -> `"power.up"`.
+pub(crate) fn command_key(command: &Command) -> String {
+    match command {
+        Power(s) => format!("power.{}", sub_command_key(s)),
+        Eco(s) => format!("eco.{}", sub_command_key(s)),
+        Brightness(s) => format!("brightness.{}", sub_command_key(s)),
+        Volume(s) => format!("volume.{}", sub_command_key(s)),
+        Mute(s) => format!("mute.{}", sub_command_key(s)),
+        Source(s) => format!("source.{}", sub_command_key(s)),
+        LampTime => "lamp_time".to_string(),
+    }
+}
+
+fn sub_command_key(sub_command: &SubCommand) -> &'static str {
+    match sub_command {
+        Up => "up",
+        Down => "down",
+        Status => "status",
+    }
+}
+
+/// The cache key for `command`'s `Status` counterpart, e.g. both `Volume(Up)`
+/// and `Volume(Status)` map to `"volume.status"`. Only `Status` commands are
+/// ever cached, so [`crate::Controllers::write`] uses this rather than
+/// [`command_key`] to invalidate the entry a write actually affects.
+pub(crate) fn status_key(command: &Command) -> String {
+    match command {
+        Power(_) => command_key(&Power(Status)),
+        Eco(_) => command_key(&Eco(Status)),
+        Brightness(_) => command_key(&Brightness(Status)),
+        Volume(_) => command_key(&Volume(Status)),
+        Mute(_) => command_key(&Mute(Status)),
+        Source(_) => command_key(&Source(Status)),
+        LampTime => command_key(&LampTime),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_key_maps_any_sub_command_to_its_status_variant() {
+        assert_eq!(status_key(&Volume(Up)), command_key(&Volume(Status)));
+        assert_eq!(status_key(&Volume(Down)), command_key(&Volume(Status)));
+        assert_eq!(status_key(&Volume(Status)), command_key(&Volume(Status)));
+        assert_eq!(status_key(&LampTime), command_key(&LampTime));
+    }
+
+    #[test]
+    fn benq_frames_match_known_literals() {
+        let model = ProjectorModel::benq();
+        assert_eq!(
+            model.frame_for(&Power(Status)).unwrap().frame,
+            vec![0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x11, 0x00]
+        );
+        assert_eq!(
+            model.frame_for(&Power(Up)).unwrap().frame,
+            vec![0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x11, 0x00, 0x00]
+        );
+        assert_eq!(
+            model.frame_for(&Volume(Down)).unwrap().frame,
+            vec![0x06, 0x14, 0x00, 0x04, 0x00, 0x34, 0x14, 0x02, 0x00]
+        );
+        assert_eq!(
+            model.frame_for(&LampTime).unwrap().frame,
+            vec![0x07, 0x14, 0x00, 0x05, 0x00, 0x34, 0x00, 0x00, 0x15, 0x01]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Bridges a [`Controller`] to an MQTT broker. Command topics are subscribed
+//! and mapped onto [`Command`]/[`SubCommand`] writes, and state topics are
+//! published periodically from [`Controller::read`] so the projector shows
+//! up as Home Assistant entities.
+
+use crate::{Command, Controller, ControllerErr, Reply, SubCommand};
+use log::{info, warn};
+use rumqttc::{Client, Connection, Event, MqttOptions, Packet, Publish, QoS};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use SubCommand::*;
+
+/// Which Home Assistant MQTT discovery domain a bridged command is
+/// published under, since that determines both the discovery config
+/// payload and how [`publish_reply`] formats the state payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HaDomain {
+    /// An on/off [`Reply::State`], settable via `Up`/`Down` writes.
+    Switch,
+    /// A read-only numeric value ([`Reply::ValueU8`]/[`Reply::ValueU32`])
+    /// that can only be nudged via relative `Up`/`Down` writes, so it's
+    /// exposed for display only rather than as a directly settable entity.
+    Sensor,
+}
+
+impl HaDomain {
+    fn discovery_component(self) -> &'static str {
+        match self {
+            HaDomain::Switch => "switch",
+            HaDomain::Sensor => "sensor",
+        }
+    }
+}
+
+/// The commands exposed over MQTT, alongside the topic-friendly name used
+/// to build their `<prefix>/<name>/set` and `<prefix>/<name>/state` topics
+/// and the Home Assistant domain their state is published under.
+const BRIDGED_COMMANDS: &[(&str, HaDomain, fn(SubCommand) -> Command)] = &[
+    ("power", HaDomain::Switch, Command::Power),
+    ("eco", HaDomain::Switch, Command::Eco),
+    ("brightness", HaDomain::Sensor, Command::Brightness),
+    ("volume", HaDomain::Sensor, Command::Volume),
+    ("mute", HaDomain::Switch, Command::Mute),
+    ("source", HaDomain::Sensor, Command::Source),
+];
+
+pub struct MqttBridge {
+    client: Client,
+    controller: Arc<Mutex<Controller>>,
+    topic_prefix: String,
+    poll_interval: Duration,
+}
+
+impl MqttBridge {
+    /// Connects to `broker_host:broker_port` and subscribes to every
+    /// `<topic_prefix>/<command>/set` topic. Call [`MqttBridge::run`] with
+    /// the returned [`Connection`] to start bridging.
+    pub fn new(
+        broker_host: &str,
+        broker_port: u16,
+        topic_prefix: &str,
+        poll_interval: Duration,
+        controller: Arc<Mutex<Controller>>,
+    ) -> Result<(MqttBridge, Connection), ControllerErr> {
+        let mut options = MqttOptions::new("projectorctl", broker_host, broker_port);
+        options.set_keep_alive(Duration::from_secs(30));
+
+        let (client, connection) = Client::new(options, 10);
+        for (name, _, _) in BRIDGED_COMMANDS {
+            let topic = format!("{}/{}/set", topic_prefix, name);
+            if let Err(e) = client.subscribe(&topic, QoS::AtLeastOnce) {
+                warn!("Can't subscribe to {}: {:?}", topic, e);
+                return Err(ControllerErr::Io(format!(
+                    "can't subscribe to {}: {}",
+                    topic, e
+                )));
+            }
+        }
+
+        let bridge = MqttBridge {
+            client,
+            controller,
+            topic_prefix: topic_prefix.to_string(),
+            poll_interval,
+        };
+        Ok((bridge, connection))
+    }
+
+    /// Publishes Home Assistant MQTT discovery messages for every bridged
+    /// command, then drives `connection`, dispatching incoming `set`
+    /// messages and periodically publishing fresh state. Blocks forever.
+    pub fn run(mut self, mut connection: Connection) {
+        self.publish_discovery();
+
+        let poll_client = self.client.clone();
+        let poll_controller = Arc::clone(&self.controller);
+        let poll_prefix = self.topic_prefix.clone();
+        let poll_interval = self.poll_interval;
+        thread::spawn(move || loop {
+            publish_state(&poll_client, &poll_prefix, &poll_controller);
+            thread::sleep(poll_interval);
+        });
+
+        for notification in connection.iter() {
+            match notification {
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    self.handle_publish(&publish);
+                }
+                Ok(_) => {}
+                Err(e) => warn!("MQTT connection error: {:?}", e),
+            }
+        }
+    }
+
+    fn handle_publish(&mut self, publish: &Publish) {
+        let command = match command_for_topic(&self.topic_prefix, &publish.topic, &publish.payload)
+        {
+            Ok(c) => c,
+            Err(e) => {
+                warn!(
+                    "Can't map MQTT message on {} to a command: {:?}",
+                    publish.topic, e
+                );
+                return;
+            }
+        };
+        let mut controller = match self.controller.lock() {
+            Ok(c) => c,
+            Err(_) => {
+                warn!("Controller mutex poisoned, dropping MQTT command");
+                return;
+            }
+        };
+        match controller.write(&command) {
+            Ok(_) => info!("Set {:?} from MQTT", command),
+            Err(e) => warn!("Can't write {:?} from MQTT: {:?}", command, e),
+        }
+    }
+
+    fn publish_discovery(&mut self) {
+        for (name, domain, _) in BRIDGED_COMMANDS {
+            let config_topic = format!(
+                "homeassistant/{}/projectorctl_{}/config",
+                domain.discovery_component(),
+                name
+            );
+            let payload = match domain {
+                HaDomain::Switch => json!({
+                    "name": format!("Projector {}", name),
+                    "unique_id": format!("projectorctl_{}", name),
+                    "command_topic": format!("{}/{}/set", self.topic_prefix, name),
+                    "state_topic": format!("{}/{}/state", self.topic_prefix, name),
+                    "payload_on": "ON",
+                    "payload_off": "OFF",
+                    "device": {
+                        "identifiers": ["projectorctl"],
+                        "name": "Projector",
+                    },
+                }),
+                HaDomain::Sensor => json!({
+                    "name": format!("Projector {}", name),
+                    "unique_id": format!("projectorctl_{}", name),
+                    "state_topic": format!("{}/{}/state", self.topic_prefix, name),
+                    "device": {
+                        "identifiers": ["projectorctl"],
+                        "name": "Projector",
+                    },
+                }),
+            };
+            if let Err(e) =
+                self.client
+                    .publish(&config_topic, QoS::AtLeastOnce, true, payload.to_string())
+            {
+                warn!(
+                    "Can't publish discovery config on {}: {:?}",
+                    config_topic, e
+                );
+            }
+        }
+    }
+}
+
+fn publish_state(client: &Client, topic_prefix: &str, controller: &Arc<Mutex<Controller>>) {
+    let mut controller = match controller.lock() {
+        Ok(c) => c,
+        Err(_) => {
+            warn!("Controller mutex poisoned, skipping MQTT state poll");
+            return;
+        }
+    };
+    for (name, domain, make_command) in BRIDGED_COMMANDS {
+        let command = make_command(Status);
+        match controller.read(&command) {
+            Ok(reply) => publish_reply(client, topic_prefix, name, *domain, &reply),
+            Err(ControllerErr::PowerIsDown) => {}
+            Err(e) => warn!("Can't read {:?} for MQTT state: {:?}", command, e),
+        }
+    }
+}
+
+/// Publishes `reply` on `name`'s state topic in the format its HA `domain`
+/// expects: `ON`/`OFF` for a switch's [`Reply::State`], the bare value
+/// otherwise.
+fn publish_reply(client: &Client, topic_prefix: &str, name: &str, domain: HaDomain, reply: &Reply) {
+    let topic = format!("{}/{}/state", topic_prefix, name);
+    let payload = match (domain, reply) {
+        (HaDomain::Switch, Reply::State(true)) => "ON".to_string(),
+        (HaDomain::Switch, Reply::State(false)) => "OFF".to_string(),
+        _ => reply.to_string(),
+    };
+    if let Err(e) = client.publish(&topic, QoS::AtLeastOnce, true, payload) {
+        warn!("Can't publish state on {}: {:?}", topic, e);
+    }
+}
+
+fn command_for_topic(
+    topic_prefix: &str,
+    topic: &str,
+    payload: &[u8],
+) -> Result<Command, ControllerErr> {
+    let name = topic
+        .strip_prefix(topic_prefix)
+        .and_then(|t| t.strip_prefix('/'))
+        .and_then(|t| t.strip_suffix("/set"))
+        .ok_or_else(|| ControllerErr::UnsupportedCommand(format!("unexpected topic {}", topic)))?;
+    let (_, _, make_command) = BRIDGED_COMMANDS
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .ok_or_else(|| {
+            ControllerErr::UnsupportedCommand(format!("unknown topic command {}", name))
+        })?;
+    let sub_command = match std::str::from_utf8(payload).unwrap_or("").trim() {
+        "Up" | "ON" | "on" => Up,
+        "Down" | "OFF" | "off" => Down,
+        other => {
+            return Err(ControllerErr::UnsupportedCommand(format!(
+                "unrecognized payload {:?}",
+                other
+            )))
+        }
+    };
+    Ok(make_command(sub_command))
+}